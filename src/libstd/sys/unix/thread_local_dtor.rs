@@ -0,0 +1,148 @@
+// Copyright 2014-2015 The Rust Project Developers. See the COPYRIGHT
+// file at the top-level directory of this distribution and at
+// http://rust-lang.org/COPYRIGHT.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Platform-specific registration of thread-local destructors, with no
+//! dependency on the `Key<T>` bookkeeping in `fast_thread_local`.
+//!
+//! Compiler-generated `#[thread_local] static` drops (and any other
+//! internal consumer that just needs a destructor run once) can call
+//! `register_dtor` directly, instead of allocating and maintaining a
+//! full `Key<T>`.
+
+#![unstable(feature = "thread_local_internals", issue = "0")]
+#![cfg(target_thread_local)]
+
+use ptr;
+
+// Mirrors the bounded `PTHREAD_DESTRUCTOR_ITERATIONS` behavior mandated by
+// POSIX: a destructor that keeps re-registering TLS values (or a platform
+// that re-seeds TLS mid-teardown, like the macOS re-initialization hazard
+// noted in `destroy_value`) must not be able to spin `run_dtors` forever.
+#[cfg(any(target_os = "linux", target_os = "fuchsia", target_os = "redox"))]
+const MAX_DTOR_ITERATIONS: usize = 8;
+
+#[cfg(any(target_os = "linux", target_os = "fuchsia", target_os = "redox"))]
+unsafe fn register_dtor_fallback(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
+    // The fallback implementation uses a vanilla OS-based TLS key to track
+    // the list of destructors that need to be run for this thread. The key
+    // then has its own destructor which runs all the other destructors.
+    //
+    // The destructor for DTORS is a little special in that it has a `while`
+    // loop to continuously drain the list of registered destructors. This
+    // loop is bounded to `MAX_DTOR_ITERATIONS` rounds: we'd *like* it to
+    // always terminate on its own, since a TLS key cannot be set after it
+    // is flagged for destruction, but that invariant doesn't hold up
+    // against a destructor that re-registers itself, so the cap below is
+    // what actually keeps this from spinning forever.
+    use sys_common::thread_local as os;
+
+    static DTORS: os::StaticKey = os::StaticKey::new(Some(run_dtors));
+    type List = Vec<(*mut u8, unsafe extern fn(*mut u8))>;
+    if DTORS.get().is_null() {
+        let v: Box<List> = box Vec::new();
+        DTORS.set(Box::into_raw(v) as *mut u8);
+    }
+    let list: &mut List = &mut *(DTORS.get() as *mut List);
+    list.push((t, dtor));
+
+    unsafe extern fn run_dtors(mut ptr: *mut u8) {
+        let mut iterations = 0;
+        while !ptr.is_null() {
+            let list: Box<List> = Box::from_raw(ptr as *mut List);
+            for &(ptr, dtor) in list.iter() {
+                dtor(ptr);
+            }
+            iterations += 1;
+            if iterations >= MAX_DTOR_ITERATIONS {
+                // We've hit the cap. Anything re-registered while running
+                // the destructors above landed back in the real `DTORS`
+                // slot, which we haven't touched this round, so it's still
+                // there for the platform's own multi-pass teardown to pick
+                // up later. Return here rather than draining it ourselves,
+                // which is what would let this loop spin forever.
+                return
+            }
+            ptr = DTORS.get();
+            DTORS.set(ptr::null_mut());
+        }
+    }
+}
+
+// Since what appears to be glibc 2.18 this symbol has been shipped which
+// GCC and clang both use to invoke destructors in thread_local globals, so
+// let's do the same!
+//
+// Note, however, that we run on lots older linuxes, as well as cross
+// compiling from a newer linux to an older linux, so we also have a
+// fallback implementation to use as well.
+//
+// Due to rust-lang/rust#18804, make sure this is not generic!
+#[cfg(target_os = "linux")]
+pub unsafe fn register_dtor(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
+    use mem;
+    use libc;
+
+    extern {
+        #[linkage = "extern_weak"]
+        static __dso_handle: *mut u8;
+        #[linkage = "extern_weak"]
+        static __cxa_thread_atexit_impl: *const libc::c_void;
+    }
+    if !__cxa_thread_atexit_impl.is_null() {
+        type F = unsafe extern fn(dtor: unsafe extern fn(*mut u8),
+                                  arg: *mut u8,
+                                  dso_handle: *mut u8) -> libc::c_int;
+        mem::transmute::<*const libc::c_void, F>(__cxa_thread_atexit_impl)
+            (dtor, t, &__dso_handle as *const _ as *mut _);
+        return
+    }
+    register_dtor_fallback(t, dtor);
+}
+
+// macOS's analog of the above linux function is this _tlv_atexit function.
+// The disassembly of thread_local globals in C++ (at least produced by
+// clang) will have this show up in the output.
+#[cfg(target_os = "macos")]
+pub unsafe fn register_dtor(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
+    extern {
+        fn _tlv_atexit(dtor: unsafe extern fn(*mut u8),
+                       arg: *mut u8);
+    }
+    _tlv_atexit(dtor, t);
+}
+
+// Just use the thread_local fallback implementation, at least until there's
+// a more direct implementation.
+#[cfg(any(target_os = "fuchsia", target_os = "redox"))]
+pub unsafe fn register_dtor(t: *mut u8, dtor: unsafe extern fn(*mut u8)) {
+    register_dtor_fallback(t, dtor);
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "fuchsia", target_os = "redox")))]
+mod tests {
+    use super::register_dtor_fallback;
+    use thread;
+
+    unsafe extern fn self_reregistering_dtor(t: *mut u8) {
+        register_dtor_fallback(t, self_reregistering_dtor);
+    }
+
+    // A destructor that keeps re-registering itself must not make
+    // `run_dtors` loop forever. The internal cap only bounds a single call
+    // to `run_dtors`; the native key's own teardown may still call it again
+    // a few more times, so the property we can actually check from here is
+    // that the thread exits at all rather than hanging.
+    #[test]
+    fn run_dtors_terminates_on_self_reregistration() {
+        thread::spawn(|| unsafe {
+            register_dtor_fallback(1 as *mut u8, self_reregistering_dtor);
+        }).join().unwrap();
+    }
+}